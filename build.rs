@@ -8,7 +8,25 @@ use std::path::PathBuf;
 
 use serde_json::json;
 
+#[path = "build/helpers.rs"]
+mod helpers;
+use helpers::*;
+
 fn main() {
+    let out_path = PathBuf::from(env::var("OUT_DIR").expect("Cargo did not set OUT_DIR"));
+    check_out_dir(&out_path);
+
+    println!("cargo:rerun-if-env-changed=RIOT_SYS_STUB");
+    if env_flag("RIOT_SYS_STUB") {
+        println!("cargo:warning=RIOT_SYS_STUB is set, producing non-functional stub bindings");
+        // Dependees' build scripts expect these to be present
+        println!("cargo:CC=");
+        println!("cargo:CFLAGS=");
+        println!("cargo:MODULES=");
+        write_stub_bindings(&out_path);
+        return;
+    }
+
     let cc;
     let mut cflags;
 
@@ -25,31 +43,61 @@ fn main() {
         let parsed: Vec<Entry> = serde_json::from_reader(commands_file)
             .expect("Failed to parse RIOT_COMPILE_COMMANDS_JSON");
 
-        // Should we only pick the consensus set here?
-        let any = &parsed[0];
+        // With CCACHE set (or a similar mechanism), the compiler is not the first argument but
+        // the one after the wrapper
+        println!("cargo:rerun-if-env-changed=RIOT_CC_WRAPPERS");
+        let extra_wrappers = env::var("RIOT_CC_WRAPPERS").unwrap_or_default();
+        let cc_wrappers: Vec<&str> = ["ccache", "sccache", "distcc"]
+            .iter()
+            .copied()
+            .chain(extra_wrappers.split_whitespace())
+            .collect();
+        let commands: Vec<&[String]> = parsed
+            .iter()
+            .map(|entry| {
+                let first = entry
+                    .arguments
+                    .first()
+                    .expect("Entry in RIOT_COMPILE_COMMANDS_JSON has no arguments");
+                let first_name = std::path::Path::new(first)
+                    .file_name()
+                    .and_then(|n| n.to_str());
+                let command = if cc_wrappers.iter().any(|&w| Some(w) == first_name) {
+                    &entry.arguments[1..]
+                } else {
+                    &entry.arguments[..]
+                };
+                if command.is_empty() {
+                    panic!(
+                        "Entry in RIOT_COMPILE_COMMANDS_JSON consists only of the compiler wrapper {}",
+                        first
+                    );
+                }
+                command
+            })
+            .collect();
 
-        cc = any.arguments[0].clone();
-        cflags = shlex::join(
-            any.arguments[1..]
-                .iter()
-                .map(|s| s.as_str())
-                // Anything after -c is not CFLAGS but concrete input/output stuff
-                .take_while(|&s| s != "-c"),
-        );
+        // Entries can differ in their flags (eg. in mixed C and C++ projects); only those all of
+        // them agree on are used. The compiler is taken from the first entry.
+        cc = commands
+            .first()
+            .expect("RIOT_COMPILE_COMMANDS_JSON contains no entries")[0]
+            .clone();
+        let (consensus, dropped) = consensus_arguments(&commands);
+        if !dropped.is_empty() {
+            println!(
+                "cargo:warning=Flags not used by all entries of RIOT_COMPILE_COMMANDS_JSON are ignored: {}",
+                dropped.join(" ")
+            );
+        }
+        cflags = shlex::join(consensus);
 
         println!("cargo:rerun-if-env-changed=RIOT_USEMODULE");
         let usemodule = env::var("RIOT_USEMODULE")
             .expect("RIOT_USEMODULE is required when RIOT_COMPILE_COMMANDS_JSON is given");
-        for m in usemodule.split(" ") {
+        for m in usemodule.split_whitespace() {
             // Hack around https://github.com/RIOT-OS/RIOT/pull/16129#issuecomment-805810090
-            write!(
-                cflags,
-                " -DMODULE_{}",
-                m.to_uppercase()
-                    // avoid producing MODULE_BOARDS_COMMON_SAMDX1-ARDUINO-BOOTLOADER
-                    .replace('-', "_")
-            )
-            .unwrap();
+            write!(cflags, " -D{}", module_macro(m)).unwrap();
         }
     } else {
         cc = env::var("RIOT_CC")
@@ -71,44 +119,256 @@ fn main() {
     println!("cargo:rerun-if-changed=riot-bindgen.h");
 
     let cflags = shlex::split(&cflags).expect("Odd shell escaping in RIOT_CFLAGS");
+    // Flags that clang does not understand, or that would do harm when running bindgen or C2Rust
+    let mut cflags_blocklist = vec![
+        // non-clang flags showing up with arm cortex m3 (eg. stk3700 board), but not specific to it
+        "-Werror",
+        "-Wformat-overflow",
+        "-Wformat-truncation",
+        "-nostartfiles", // seen first on hifive1, but not specific to it
+        "-fno-delete-null-pointer-checks", // seen on an Ubuntu 18.04
+        // and much more worries on that ubuntu ... maybe just recommend TOOLCHAIN=llvm ?
+        // Don't pollute the riot-sys source directory
+        "-MD",
+    ];
+    // Architecture specific flags are only removed on their architecture, lest a flag that is
+    // valid (and important) for one gets stripped because another one needs it removed.
+    match env::var("CARGO_CFG_TARGET_ARCH")
+        .expect("Cargo did not set CARGO_CFG_TARGET_ARCH")
+        .as_str()
+    {
+        "arm" => cflags_blocklist.extend_from_slice(&[
+            // non-clang flags showing up with arm cortex m3 (eg. stk3700 board)
+            "-mno-thumb-interwork",
+        ]),
+        "riscv32" | "riscv64" => cflags_blocklist.extend_from_slice(&[
+            // non-clang flags showing up for the hifive1 board
+            "-mcmodel=medlow",
+            "-msmall-data-limit=8",
+        ]),
+        _ => (),
+    }
+    // Toolchains this crate doesn't know about can bring flags of their own
+    println!("cargo:rerun-if-env-changed=RIOT_SYS_DROP_CFLAGS");
+    let drop_cflags = env::var("RIOT_SYS_DROP_CFLAGS").unwrap_or_default();
+    cflags_blocklist.extend(drop_cflags.split_whitespace());
     let cflags: Vec<String> = cflags
         .into_iter()
-        .filter(|x| {
-            match x.as_ref() {
-                // non-clang flags showing up with arm cortex m3 (eg. stk3700 board)
-                "-Werror" => false,
-                "-mno-thumb-interwork" => false,
-                "-Wformat-overflow" => false,
-                "-Wformat-truncation" => false,
-                // non-clang flags showing up for the hifive1 board
-                "-mcmodel=medlow" => false,
-                "-msmall-data-limit=8" => false,
-                "-nostartfiles" => false, // that probably shows up on arm too, but shouldn't matter
-                "-fno-delete-null-pointer-checks" => false, // seen on an Ubuntu 18.04
-                // and much more worries on that ubuntu ... maybe just recommend TOOLCHAIN=llvm ?
-                // Don't pollute the riot-sys source directory
-                "-MD" => false,
-                // accept all others
-                _ => true,
-            }
-        })
+        .filter(|x| !cflags_blocklist.contains(&x.as_str()))
         .collect();
 
-    let bindings = builder()
+    // Without any include directories, bindgen fails on the first RIOT header with an error that
+    // does not hint at the actual cause
+    if !cflags
+        .iter()
+        .any(|f| f.starts_with("-I") || f.starts_with("-isystem"))
+    {
+        println!("cargo:warning=RIOT_CFLAGS contains no include directories -- bindings generation will almost certainly fail; did you pass the right flags?");
+    }
+
+    println!("cargo:rerun-if-env-changed=RIOT_SYS_VERBOSE");
+    let verbose = env_flag("RIOT_SYS_VERBOSE");
+
+    println!("cargo:rerun-if-env-changed=RIOT_SYS_STRICT");
+    let strict = env_flag("RIOT_SYS_STRICT");
+
+    if verbose {
+        let mut include_dirs = vec![];
+        let mut defines = vec![];
+        let mut flags = cflags.iter().map(|s| s.as_str());
+        while let Some(flag) = flags.next() {
+            // Both `-Ifoo` and `-I foo` are valid
+            if let Some(dir) = flag
+                .strip_prefix("-isystem")
+                .or_else(|| flag.strip_prefix("-I"))
+            {
+                include_dirs.push(match dir {
+                    "" => flags.next().unwrap_or(""),
+                    dir => dir,
+                });
+            } else if let Some(define) = flag.strip_prefix("-D") {
+                defines.push(match define {
+                    "" => flags.next().unwrap_or(""),
+                    define => define,
+                });
+            }
+        }
+        println!(
+            "cargo:warning=Include directories used for bindings: {}",
+            include_dirs.join(" ")
+        );
+        println!(
+            "cargo:warning=Defines used for bindings: {}",
+            defines.join(" ")
+        );
+
+        // Struct layouts come out wrong when clang (in bindgen) and the actual compiler disagree
+        // on the target. Unless the CFLAGS say otherwise, bindgen passes Cargo's target on to
+        // clang.
+        let mut flags = cflags.iter().map(|s| s.as_str());
+        let mut clang_target = None;
+        while let Some(flag) = flags.next() {
+            if let Some(target) = flag.strip_prefix("--target=") {
+                clang_target = Some(target);
+            } else if flag == "-target" {
+                clang_target = flags.next();
+            }
+        }
+        let rust_target = env::var("TARGET").expect("Cargo did not set TARGET");
+        let pointer_width = env::var("CARGO_CFG_TARGET_POINTER_WIDTH")
+            .expect("Cargo did not set CARGO_CFG_TARGET_POINTER_WIDTH");
+        match clang_target {
+            Some(clang_target) => println!(
+                "cargo:warning=bindgen targeting {} (from the CFLAGS), Rust target {}, pointer width {}",
+                clang_target, rust_target, pointer_width
+            ),
+            None => println!(
+                "cargo:warning=bindgen targeting {} (no target in the CFLAGS), pointer width {}",
+                rust_target, pointer_width
+            ),
+        }
+    }
+
+    // Some information on the build is only available as defines from the command line, which
+    // bindgen does not turn into constants.
+    let mut riot_defines = String::new();
+    println!("cargo:rustc-check-cfg=cfg(riot_version, values(any()))");
+    if let Some(version) = find_string_define(&cflags, "RIOT_VERSION") {
+        writeln!(
+            riot_defines,
+            "/// Version of RIOT the crate was built for, as passed in its RIOT_VERSION define\n\
+            pub const RIOT_VERSION: &str = {:?};",
+            version
+        )
+        .unwrap();
+
+        if let Some(release) = riot_release(version) {
+            println!("cargo:rustc-cfg=riot_version=\"{}\"", release);
+        }
+    }
+    for (name, description) in [
+        ("RIOT_BOARD", "Name of the board"),
+        ("RIOT_CPU", "Name of the CPU"),
+    ] {
+        if let Some(value) = find_string_define(&cflags, name) {
+            writeln!(
+                riot_defines,
+                "/// {} the crate was built for, as passed in RIOT's {} define\n\
+                pub const {}: &str = {:?};",
+                description, name, name, value
+            )
+            .unwrap();
+        }
+    }
+    std::fs::write(out_path.join("riot_defines.rs"), riot_defines)
+        .expect("Failed to write riot_defines.rs");
+
+    // Cargo only applies the cfgs to this crate; dependees that want to gate code on modules can
+    // read the list as DEP_RIOT_SYS_MODULES in their build scripts and set their own. The cfg is
+    // declared for any value, so that gating on an absent module does not warn.
+    let modules = find_modules(&cflags);
+    println!("cargo:rustc-check-cfg=cfg(riot_module, values(any()))");
+    for module in modules.iter() {
+        println!(
+            "cargo:rustc-cfg=riot_module=\"{}\"",
+            module.to_ascii_lowercase()
+        );
+    }
+    println!("cargo:MODULES={}", modules.join(" "));
+
+    // Written before anything can fail, so that broken builds leave a dump as well; the C2Rust
+    // arguments are filled in once it is about to run.
+    dump_config(&cc, &cflags, &cflags_blocklist, None);
+
+    // The headers bindgen sees are the ones C2Rust will see; they are recorded to tell whether
+    // C2Rust needs to be run again.
+    let included_headers = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+
+    let mut bindings_builder = builder()
         .header("riot-bindgen.h")
         .clang_args(&cflags)
         .use_core()
         .ctypes_prefix("libc")
         .impl_debug(true)
         .derive_default(true)
-        .parse_callbacks(Box::new(bindgen::CargoCallbacks))
+        .parse_callbacks(Box::new(RecordingCargoCallbacks {
+            included: included_headers.clone(),
+        }));
+
+    // Items are either a bare style (setting the default for all enums), or a style=pattern pair
+    // that applies the style to the enums whose names match the pattern.
+    println!("cargo:rerun-if-env-changed=RIOT_BINDGEN_ENUM_STYLE");
+    if let Ok(enum_styles) = env::var("RIOT_BINDGEN_ENUM_STYLE") {
+        use bindgen::EnumVariation::*;
+
+        for item in enum_styles.split_whitespace() {
+            let (style, pattern) = match item.split_once('=') {
+                Some((style, pattern)) => (style, Some(pattern)),
+                None => (item, None),
+            };
+            let style: bindgen::EnumVariation = style
+                .parse()
+                .expect("Invalid style in RIOT_BINDGEN_ENUM_STYLE");
+            bindings_builder = match (style, pattern) {
+                (style, None) => bindings_builder.default_enum_style(style),
+                (Rust { non_exhaustive }, Some(p)) => match non_exhaustive {
+                    false => bindings_builder.rustified_enum(p),
+                    true => bindings_builder.rustified_non_exhaustive_enum(p),
+                },
+                (NewType { is_bitfield: false }, Some(p)) => bindings_builder.newtype_enum(p),
+                (NewType { is_bitfield: true }, Some(p)) => bindings_builder.bitfield_enum(p),
+                (Consts, Some(p)) => bindings_builder.constified_enum(p),
+                (ModuleConsts, Some(p)) => bindings_builder.constified_enum_module(p),
+            };
+        }
+    }
+
+    println!("cargo:rerun-if-env-changed=RIOT_BINDGEN_NO_DEFAULT");
+    if let Ok(no_default) = env::var("RIOT_BINDGEN_NO_DEFAULT") {
+        for pattern in no_default.split_whitespace() {
+            bindings_builder = bindings_builder.no_default(pattern);
+        }
+    }
+
+    let bindings = bindings_builder
         .generate()
         .expect("Unable to generate bindings");
 
-    let out_path = PathBuf::from(env::var("OUT_DIR").unwrap());
-    bindings
-        .write_to_file(out_path.join("bindings.rs"))
-        .expect("Couldn't write bindings!");
+    // Wrapping the bindings into a module allows tools to include several generated files (eg. for
+    // different boards) side by side; the `libc` the bindings refer to is expected to be in scope
+    // where the module is included.
+    println!("cargo:rerun-if-env-changed=RIOT_SYS_BINDINGS_MOD");
+    match env::var("RIOT_SYS_BINDINGS_MOD") {
+        Ok(module) => {
+            if !is_identifier(&module) {
+                panic!("RIOT_SYS_BINDINGS_MOD needs to be a Rust identifier");
+            }
+            std::fs::write(
+                out_path.join("bindings.rs"),
+                format!(
+                    "pub mod {} {{\nuse super::libc;\n{}\n}}\n",
+                    module, bindings
+                ),
+            )
+            .expect("Couldn't write bindings!");
+        }
+        Err(_) => bindings
+            .write_to_file(out_path.join("bindings.rs"))
+            .expect("Couldn't write bindings!"),
+    }
+
+    // RIOT_SYS_BINDGEN_ONLY is for diagnostics, RIOT_SYS_SKIP_C2RUST for setups that can live
+    // without the inline functions; they only differ in intention.
+    println!("cargo:rerun-if-env-changed=RIOT_SYS_BINDGEN_ONLY");
+    println!("cargo:rerun-if-env-changed=RIOT_SYS_SKIP_C2RUST");
+    if let Some(var) = ["RIOT_SYS_BINDGEN_ONLY", "RIOT_SYS_SKIP_C2RUST"]
+        .iter()
+        .find(|var| env_flag(var))
+    {
+        println!("cargo:warning={} is set, C2Rust was not run; static inline and macro_ functions are unavailable", var);
+        write_empty_inline(&out_path, &format!("{} was set", var));
+        return;
+    }
 
     // Build a compile_commands.json, and run C2Rust
     //
@@ -146,6 +406,40 @@ fn main() {
         macro_functions.push((format!("LED{}_TOGGLE", i), "void", "void", false));
     }
 
+    // Applications can add initializers for macros of their own modules through a JSON file like
+    // `[{"macro": "MY_MODULE_INIT", "type": "my_module_t"}]`.
+    #[derive(Debug, serde::Deserialize)]
+    struct ExtraInitializer {
+        #[serde(rename = "macro")]
+        macro_name: String,
+        #[serde(rename = "type")]
+        return_type: String,
+    }
+    println!("cargo:rerun-if-env-changed=RIOT_SYS_EXTRA_INITIALIZERS");
+    let extra_initializers: Vec<ExtraInitializer> = match env::var("RIOT_SYS_EXTRA_INITIALIZERS") {
+        Ok(path) => {
+            println!("cargo:rerun-if-changed={}", path);
+            let file = std::fs::File::open(&path)
+                .expect("Failed to open RIOT_SYS_EXTRA_INITIALIZERS file");
+            serde_json::from_reader(file).expect("Failed to parse RIOT_SYS_EXTRA_INITIALIZERS file")
+        }
+        Err(_) => vec![],
+    };
+    for initializer in extra_initializers.iter() {
+        if !is_identifier(&initializer.macro_name) {
+            panic!(
+                "Initializer macro {:?} in RIOT_SYS_EXTRA_INITIALIZERS is not an identifier",
+                initializer.macro_name
+            );
+        }
+        macro_functions.push((
+            initializer.macro_name.clone(),
+            initializer.return_type.as_str(),
+            "void",
+            true,
+        ));
+    }
+
     let mut c_code = String::new();
     std::fs::File::open("riot-c2rust.h")
         .expect("Failed to open riot-c2rust.h")
@@ -205,55 +499,20 @@ fn main() {
         .sync_all()
         .expect("failed to write to riot-c2rust.h");
 
-    let c2rust_infile;
-    let c2rust_outfile;
-    if cc.find("clang") == None {
-        // Run through preprocessor with platform specific arguments (cf.
-        // <https://github.com/immunant/c2rust/issues/305>)
-        //
-        // This is only done for non-clang setups; those do not need it (and can profit from the
-        // unexpanded macros). Also, clang does not have "-fdirectives-only' (but their
-        // "-frewrite-includes" might do as well if it turns out that this *is* needed even there).
-        let preprocessed_headercopy = out_path.join("riot-c2rust-expanded.h");
-        let clang_e_args: Vec<_> = cflags
-            .iter()
-            .map(|s| s.clone())
-            .chain(
-                vec![
-                    "-E",
-                    "-fdirectives-only",
-                    headercopy.to_str().expect("Non-string path for headercopy"),
-                    "-o",
-                    preprocessed_headercopy
-                        .to_str()
-                        .expect("Non-string path in preprocessed_headercopy"),
-                ]
-                .drain(..)
-                .map(|x| x.to_string()),
-            )
-            .collect();
-        let status = std::process::Command::new(cc)
-            .args(clang_e_args)
-            .status()
-            .expect("Preprocessor run failed");
-        if !status.success() {
-            println!(
-                "cargo:warning=Preprocessor failed with error code {}, exiting",
-                status
-            );
-            std::process::exit(status.code().unwrap_or(1));
-        }
-        c2rust_infile = "riot-c2rust-expanded.h";
-        c2rust_outfile = "riot_c2rust_expanded.rs";
+    // Non-clang setups need the header run through their preprocessor first (see below)
+    let c2rust_infile = if cc.find("clang") == None {
+        "riot-c2rust-expanded.h"
     } else {
-        c2rust_infile = "riot-c2rust.h";
-        c2rust_outfile = "riot_c2rust.rs";
-    }
+        "riot-c2rust.h"
+    };
 
-    let output = out_path.join(c2rust_outfile);
+    // The output file name is not chosen here but follows from the input file. Whatever it is, the
+    // fixed-up result is always written to riot_c2rust_replaced.rs, which is what the inline module
+    // includes, so the input name never shows in the crate's structure.
+    let output = out_path.join(c2rust_output_name(c2rust_infile));
 
     let arguments: Vec<_> = core::iter::once("any-cc".to_string())
-        .chain(cflags.into_iter())
+        .chain(cflags.iter().cloned())
         .chain(core::iter::once(c2rust_infile.to_string()))
         .collect();
     let compile_commands = json!([{
@@ -276,26 +535,112 @@ fn main() {
         .expect("Inexpressible path name");
     // FIXME: This does not rat on the used files. Most are probably included from riot-bindgen.h
     // anyway, tough.
-    println!("Running C2Rust on {}", compile_commands_name);
-    let status = std::process::Command::new("c2rust")
-        .args(&[
-            "transpile",
-            compile_commands_name,
-            "--preserve-unused-functions",
-            "--emit-modules",
-            "--emit-no-std",
-            "--translate-const-macros",
-            "--overwrite-existing",
-            "--fail-on-error",
-        ])
-        .status()
-        .expect("C2Rust failed");
-    if !status.success() {
-        println!(
-            "cargo:warning=C2Rust failed with error code {}, exiting",
-            status
+    let c2rust_arguments = [
+        "transpile",
+        compile_commands_name,
+        "--preserve-unused-functions",
+        "--emit-modules",
+        "--emit-no-std",
+        "--translate-const-macros",
+        "--overwrite-existing",
+        "--fail-on-error",
+    ];
+
+    dump_config(&cc, &cflags, &cflags_blocklist, Some(&c2rust_arguments));
+
+    println!("cargo:rerun-if-env-changed=RIOT_C2RUST_BIN");
+    println!("cargo:rerun-if-env-changed=C2RUST");
+    let c2rust = env::var("RIOT_C2RUST_BIN")
+        .or_else(|_| env::var("C2RUST"))
+        .unwrap_or_else(|_| "c2rust".to_string());
+
+    // C2Rust takes by far the longest of all steps, and the build script is rerun for many reasons
+    // that don't affect its output. The raw output is thus kept along with a record of everything
+    // it was produced from (the generated C code covers the macro_functions table), and reused if
+    // that matches. Of the headers (as of the C2Rust binary), only the modification times are
+    // recorded.
+    println!("cargo:rerun-if-changed=riot-headers.h");
+    let mut included_headers = included_headers
+        .lock()
+        .expect("Bindgen panicked while recording headers")
+        .clone();
+    included_headers.sort();
+    included_headers.dedup();
+    let included_headers: Vec<_> = included_headers
+        .iter()
+        .map(|header| json!([header, modification_time(header.as_ref())]))
+        .collect();
+    let inputs = json!({
+        "cc": cc,
+        "cflags": cflags,
+        "c_code": c_code,
+        "riot_headers": std::fs::read_to_string("riot-headers.h")
+            .expect("Failed to read riot-headers.h"),
+        "included_headers": included_headers,
+        "c2rust": c2rust,
+        "c2rust_modified": find_program(&c2rust).and_then(|path| modification_time(&path)),
+        "c2rust_arguments": c2rust_arguments,
+    })
+    .to_string();
+    let inputs_file = out_path.join(format!("{}.inputs.json", c2rust_output_name(c2rust_infile)));
+    let cached = output.exists()
+        && std::fs::read_to_string(&inputs_file)
+            .map(|previous| previous == inputs)
+            .unwrap_or(false);
+
+    if cached {
+        println!("Reusing C2Rust output from a previous run");
+    } else {
+        // Whatever is in the output now is not what the record describes any more once a run fails
+        // halfway.
+        let _ = std::fs::remove_file(&inputs_file);
+
+        if c2rust_infile == "riot-c2rust-expanded.h" {
+            // Run through preprocessor with platform specific arguments (cf.
+            // <https://github.com/immunant/c2rust/issues/305>)
+            //
+            // This is only done for non-clang setups; those do not need it (and can profit from the
+            // unexpanded macros). Also, clang does not have "-fdirectives-only' (but their
+            // "-frewrite-includes" might do as well if it turns out that this *is* needed even
+            // there).
+            let preprocessed_headercopy = out_path.join(c2rust_infile);
+            let clang_e_args: Vec<_> = cflags
+                .iter()
+                .map(|s| s.clone())
+                .chain(
+                    vec![
+                        "-E",
+                        "-fdirectives-only",
+                        headercopy.to_str().expect("Non-string path for headercopy"),
+                        "-o",
+                        preprocessed_headercopy
+                            .to_str()
+                            .expect("Non-string path in preprocessed_headercopy"),
+                    ]
+                    .drain(..)
+                    .map(|x| x.to_string()),
+                )
+                .collect();
+            run_tool(
+                "Preprocessor",
+                std::process::Command::new(&cc).args(clang_e_args),
+                "check RIOT_CC (or the compiler in RIOT_COMPILE_COMMANDS_JSON)",
+                strict,
+            );
+        }
+
+        println!("Running {} on {}", c2rust, compile_commands_name);
+        run_tool(
+            "C2Rust",
+            std::process::Command::new(&c2rust).args(c2rust_arguments),
+            "install it as described in riot-sys' README.md (the for-riot branch of \
+            https://github.com/chrysn-pull-requests/c2rust/, built with nightly-2019-12-05, is what \
+            is tested), point RIOT_C2RUST_BIN to it, or set RIOT_SYS_SKIP_C2RUST=1 to build \
+            without the static inline functions",
+            strict,
         );
-        std::process::exit(status.code().unwrap_or(1));
+
+        std::fs::write(&inputs_file, &inputs).expect("Failed to write C2Rust inputs record");
     }
 
     // Some fix-ups to the C2Rust output
@@ -317,75 +662,37 @@ fn main() {
     // This only matches when c2rust is built to even export body-less functions
     rustcode = rustcode.replace("    #[no_mangle]\n    fn ", "    #[no_mangle]\n    pub fn ");
 
-    // The GCLK global constant interferes with arguments and fields named GCLK in bindgen, as
-    // these would (due to the `use inline::*;` blanket import) try to make that into a matchable
-    // pattern. Renaming the whole constant.
+    // Global constants like GCLK interfere with arguments and fields of the same name in bindgen,
+    // as these would (due to the `use inline::*;` blanket import) try to make that into a
+    // matchable pattern. Such constants are renamed as a whole; `$0` in the replacement stands for
+    // the original name.
     //
-    // This is needed, for example, on the adafruit-itsybitsy-m4 board with the riot-hello-world
-    // example.
-    //
-    // Likewise, the SERCOM constants are set by the samr30-xpro board.
-    let gclk_word = regex::Regex::new(r"\bGCLK\b").expect("Invalid static regular expression");
-    rustcode = gclk_word.replace_all(&rustcode, "GLOBAL_GCLK").to_string();
-    let sercom_word =
-        regex::Regex::new(r"\bSERCOM[0-9]\b").expect("Invalid static regular expression");
-    rustcode = sercom_word.replace_all(&rustcode, "GLOBAL_$0").to_string();
-
-    // Replace the function declarations with ... usually something pub, but special considerations
-    // may apply
-    let mut rustcode_functionsreplaced = String::new();
-    let function_original_prefix = r#"unsafe extern "C" fn "#;
-    let mut functionchunks = rustcode.split(function_original_prefix);
-    rustcode_functionsreplaced.push_str(
-        functionchunks
-            .next()
-            .expect("Split produces at least a hit"),
-    );
-
-    for chunk in functionchunks {
-        let funcname = &chunk[..chunk.find('(').expect("Function has parentheses somewhere")];
-        let macro_details = if funcname.len() > 5 && &funcname[..6] == "macro_" {
-            macro_functions
-                .iter()
-                .filter(|(macro_name, _, _, _)| &funcname[6..] == *macro_name)
-                .next()
-        } else {
-            None
-        };
-        let new_prefix = match (funcname, macro_details) {
-            // used as a callback, therefore does need the extern "C" -- FIXME probably worth a RIOT issue
-            ("_evtimer_msg_handler" | "_evtimer_mbox_handler", _) => function_original_prefix,
-
-            // Assigned by CMSIS to some const; see also riot-c2rust.h
-            ("__masked_builtin_arm_get_fpscr" | "__masked_builtin_arm_set_fpscr", _) => {
-                function_original_prefix
-            }
-
-            // same problem but from C2Rust's --translate-const-macros
-            ("__NVIC_SetPriority", _) => function_original_prefix,
-
-            // As below (no need for extern), and they are const as declared ni the macro_functions
-            // list.
-            (_, Some((_, _, _, is_const))) => {
-                // No "pub" because that's already a "pub" in front of it, they were never static
-                match is_const {
-                    // FIXME: These should be unsafe -- just because most of them are const doesn't
-                    // necessrily mean they're safe (just the first few happened to be, but that's
-                    // not this crate's place to assert)
-                    true => "const unsafe fn ",
-                    false => "unsafe fn ",
-                }
-            }
-
-            // The rest we don't need to call through the extern convention, but let's please make
-            // them pub to be usable
-            _ => "pub unsafe fn ",
-        };
-        rustcode_functionsreplaced.push_str(new_prefix);
-        rustcode_functionsreplaced.push_str(chunk);
+    // GCLK is needed, for example, on the adafruit-itsybitsy-m4 board with the riot-hello-world
+    // example. Likewise, the SERCOM constants are set by the samr30-xpro board. Boards with other
+    // clashes can add renames in RIOT_SYS_RENAME_GLOBALS, as space separated pattern=replacement
+    // items.
+    let mut renames = vec![("GCLK", "GLOBAL_$0"), ("SERCOM#", "GLOBAL_$0")];
+    println!("cargo:rerun-if-env-changed=RIOT_SYS_RENAME_GLOBALS");
+    let extra_renames = env::var("RIOT_SYS_RENAME_GLOBALS").unwrap_or_default();
+    for item in extra_renames.split_whitespace() {
+        renames.push(
+            item.split_once('=')
+                .expect("RIOT_SYS_RENAME_GLOBALS items need to be pattern=replacement"),
+        );
     }
+    rustcode = replace_words(&rustcode, |word| {
+        renames
+            .iter()
+            .find(|(pattern, _)| glob_matches(pattern, word))
+            .map(|(_, replacement)| replacement.replace("$0", word))
+    });
 
-    rustcode = rustcode_functionsreplaced;
+    // Functions that are transpiler artifacts rather than RIOT API, and are thus not made pub
+    println!("cargo:rerun-if-env-changed=RIOT_C2RUST_PRIVATE_PATTERNS");
+    let private_patterns = env::var("RIOT_C2RUST_PRIVATE_PATTERNS").unwrap_or_default();
+    let private_patterns: Vec<&str> = private_patterns.split_whitespace().collect();
+
+    rustcode = rewrite_function_prefixes(&rustcode, &macro_functions, &private_patterns);
 
     let output_replaced = out_path.join("riot_c2rust_replaced.rs");
     std::fs::File::create(output_replaced)
@@ -393,6 +700,16 @@ fn main() {
         .write(rustcode.as_bytes())
         .expect("Failed to write to riot_c2rust_replaced.rs");
 
+    let bindings_code = std::fs::read_to_string(out_path.join("bindings.rs"))
+        .expect("Failed to read back bindings.rs");
+    let collisions = find_name_collisions(&rustcode, &bindings_code);
+    if !collisions.is_empty() {
+        println!(
+            "cargo:warning=Constants from C2Rust that are also field or argument names in the bindings (these may need renaming like GCLK): {}",
+            collisions.join(", ")
+        );
+    }
+
     // Pub uses of inline right into the main lib.rs
     //
     // This is primarily for things that can really come from either backend (eg. irq functions
@@ -405,9 +722,16 @@ fn main() {
         "coap_get_code_raw",
         "coap_get_total_hdr_len",
         "gnrc_netapi_dispatch_send",
+        "gnrc_netif_hdr_set_netif",
+        "gnrc_netif_ipv6_addr_add",
+        "gnrc_netif_ipv6_addr_remove",
         "gnrc_netif_ipv6_addrs_get",
         "gnrc_netreg_entry_init_pid",
+        "gnrc_pktbuf_release",
         "gpio_is_valid",
+        "inet_csum",
+        "ipv6_addr_is_ipv4_compat",
+        "ipv6_addr_is_ipv4_mapped",
         "irq_disable",
         "irq_is_enabled",
         "irq_is_in",
@@ -420,6 +744,8 @@ fn main() {
         "thread_get",
         "thread_getpid",
         "thread_get_unchecked",
+        "xtimer_now_usec",
+        "xtimer_usleep",
         "ztimer_spin",
     ]
     .iter()
@@ -446,3 +772,230 @@ fn main() {
         )
         .expect("Failed to write to toplevel_from_inline.rs");
 }
+
+/// Check whether a switch like RIOT_SYS_STRICT is turned on
+///
+/// See [`flag_enabled`] for which values count as on.
+fn env_flag(name: &str) -> bool {
+    env::var(name).map(|v| flag_enabled(&v)).unwrap_or(false)
+}
+
+/// Make sure that writing to OUT_DIR does not affect the sources
+///
+/// Some of the files placed in OUT_DIR have the same names as the headers they are produced from,
+/// so an OUT_DIR that is the crate directory itself would silently overwrite them. (Whether OUT_DIR
+/// is writable at all is not checked up front; the first write fails clearly enough.)
+fn check_out_dir(out_path: &std::path::Path) {
+    let out_dir = out_path
+        .canonicalize()
+        .expect("OUT_DIR does not exist or is inaccessible");
+    let source_dir = PathBuf::from(
+        env::var("CARGO_MANIFEST_DIR").expect("Cargo did not set CARGO_MANIFEST_DIR"),
+    )
+    .canonicalize()
+    .expect("Crate directory is inaccessible");
+    if out_dir == source_dir {
+        panic!(
+            "OUT_DIR is the riot-sys source directory ({}); refusing to overwrite files in there",
+            source_dir.display()
+        );
+    }
+}
+
+/// Parse callbacks that tell Cargo about every included file like bindgen's CargoCallbacks do, and
+/// also record those files
+#[derive(Debug)]
+struct RecordingCargoCallbacks {
+    included: std::sync::Arc<std::sync::Mutex<Vec<String>>>,
+}
+
+impl bindgen::callbacks::ParseCallbacks for RecordingCargoCallbacks {
+    fn include_file(&self, filename: &str) {
+        bindgen::CargoCallbacks.include_file(filename);
+        self.included
+            .lock()
+            .expect("Lock poisoned")
+            .push(filename.to_string());
+    }
+}
+
+/// Find the file a program name would be run from, looking through the PATH unless it contains
+/// a path already
+fn find_program(program: &str) -> Option<PathBuf> {
+    if program.contains(std::path::MAIN_SEPARATOR) {
+        return Some(PathBuf::from(program));
+    }
+    env::split_paths(&env::var_os("PATH")?)
+        .map(|dir| dir.join(program))
+        .find(|path| path.is_file())
+}
+
+/// Last modification time of a file, if it can be found
+fn modification_time(path: &std::path::Path) -> Option<std::time::SystemTime> {
+    std::fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+/// Write out the generated files the crate includes, but without looking at any RIOT headers
+///
+/// This is used with `RIOT_SYS_STUB` to allow type-checking code on hosts that have no RIOT build
+/// environment. Only a handful of commonly used types are declared, and they are all opaque; no
+/// functions are available, and nothing built this way can be linked against RIOT.
+fn write_stub_bindings(out_path: &std::path::Path) {
+    let bindings = r#"
+// Stub bindings produced because RIOT_SYS_STUB was set -- these are not functional.
+
+pub type int16_t = libc::c_short;
+pub type kernel_pid_t = int16_t;
+pub type gnrc_nettype_t = libc::c_int;
+
+// Only the byte view of the union is declared
+#[repr(C)]
+#[derive(Copy, Clone)]
+pub union ipv6_addr_t {
+    pub u8: [u8; 16usize],
+}
+
+#[repr(C)]
+pub struct gnrc_pktsnip_t {
+    _private: [u8; 0],
+}
+
+#[repr(C)]
+pub struct gnrc_netif_t {
+    _private: [u8; 0],
+}
+
+#[repr(C)]
+pub struct msg_t {
+    _private: [u8; 0],
+}
+
+#[repr(C)]
+pub struct mutex_t {
+    _private: [u8; 0],
+}
+
+#[repr(C)]
+pub struct ztimer_clock_t {
+    _private: [u8; 0],
+}
+
+extern "C" {
+    pub fn ipv6_addr_from_str(
+        result: *mut ipv6_addr_t,
+        addr: *const libc::c_char,
+    ) -> *mut ipv6_addr_t;
+    pub fn ipv6_addr_to_str(
+        result: *mut libc::c_char,
+        addr: *const ipv6_addr_t,
+        result_len: u8,
+    ) -> *mut libc::c_char;
+    pub fn gnrc_netif_iter(prev: *const gnrc_netif_t) -> *mut gnrc_netif_t;
+    pub fn gnrc_pktbuf_hold(pkt: *mut gnrc_pktsnip_t, num: libc::c_uint);
+    pub fn gnrc_pktbuf_release_error(pkt: *mut gnrc_pktsnip_t, err: u32);
+    pub fn gnrc_pktsnip_search_type(
+        pkt: *mut gnrc_pktsnip_t,
+        type_: gnrc_nettype_t,
+    ) -> *mut gnrc_pktsnip_t;
+}
+"#;
+    std::fs::write(out_path.join("bindings.rs"), bindings)
+        .expect("Failed to write stub bindings.rs");
+    write_empty_inline(out_path, "RIOT_SYS_STUB was set");
+    std::fs::write(out_path.join("riot_defines.rs"), "")
+        .expect("Failed to write stub riot_defines.rs");
+}
+
+/// Write the files the inline module and its top-level re-exports are built from for builds that
+/// do not run C2Rust
+fn write_empty_inline(out_path: &std::path::Path, reason: &str) {
+    std::fs::write(
+        out_path.join("riot_c2rust_replaced.rs"),
+        format!(
+            "// C2Rust was not run because {}; no static inline functions and no macro_ functions \
+            are available.\n",
+            reason
+        ),
+    )
+    .expect("Failed to write riot_c2rust_replaced.rs");
+    std::fs::write(out_path.join("toplevel_from_inline.rs"), "")
+        .expect("Failed to write toplevel_from_inline.rs");
+}
+
+/// Run an external tool, and end the build if it fails
+///
+/// By default, a failure is reported in a warning, and the build script exits with the tool's exit
+/// code. In strict mode, the build script panics instead, with the tool's error output in the
+/// panic message.
+///
+/// If the tool can not be found at all, the build script panics in either mode, with the hint on
+/// how to get the tool in the message.
+fn run_tool(name: &str, command: &mut std::process::Command, missing_hint: &str, strict: bool) {
+    if strict {
+        let output = command
+            .output()
+            .unwrap_or_else(|e| tool_not_run(name, command, missing_hint, e));
+        if !output.status.success() {
+            panic!(
+                "{} failed with {}; its error output was:\n{}",
+                name,
+                output.status,
+                String::from_utf8_lossy(&output.stderr)
+            );
+        }
+    } else {
+        let status = command
+            .status()
+            .unwrap_or_else(|e| tool_not_run(name, command, missing_hint, e));
+        if !status.success() {
+            println!(
+                "cargo:warning={} failed with error code {}, exiting",
+                name, status
+            );
+            std::process::exit(status.code().unwrap_or(1));
+        }
+    }
+}
+
+/// End the build after an external tool could not be started
+fn tool_not_run(
+    name: &str,
+    command: &std::process::Command,
+    missing_hint: &str,
+    error: std::io::Error,
+) -> ! {
+    let program = command.get_program().to_string_lossy();
+    if error.kind() == std::io::ErrorKind::NotFound {
+        println!("cargo:warning={} ({}) was not found", name, program);
+        panic!("{} ({}) was not found; {}", name, program, missing_hint);
+    }
+    panic!("{} ({}) could not be run: {}", name, program, error);
+}
+
+/// Write the configuration the bindings are built with into the file named in
+/// RIOT_SYS_DUMP_CONFIG, if that is set
+///
+/// The file is written before bindgen runs, without C2Rust arguments, and written again with them
+/// right before C2Rust runs; when C2Rust is not run at all, they stay absent.
+fn dump_config(
+    cc: &str,
+    cflags: &[String],
+    cflags_blocklist: &[&str],
+    c2rust_arguments: Option<&[&str]>,
+) {
+    println!("cargo:rerun-if-env-changed=RIOT_SYS_DUMP_CONFIG");
+    if let Ok(dump_path) = env::var("RIOT_SYS_DUMP_CONFIG") {
+        let modules = find_modules(cflags);
+        let config = json!({
+            "cc": cc,
+            "cflags": cflags,
+            "cflags_blocklist": cflags_blocklist,
+            "modules": modules,
+            "c2rust_arguments": c2rust_arguments,
+        });
+        let dump_file =
+            std::fs::File::create(dump_path).expect("Failed to create RIOT_SYS_DUMP_CONFIG file");
+        serde_json::to_writer_pretty(dump_file, &config)
+            .expect("Failed to write to RIOT_SYS_DUMP_CONFIG file");
+    }
+}
@@ -0,0 +1,567 @@
+//! Helpers of the build script that only work on their arguments
+//!
+//! These are kept apart from the rest of the build script so that they can be tested; see
+//! `tests/build_helpers.rs`.
+
+/// Flags whose value is passed as a separate argument, and that thus need to be kept together with
+/// that argument
+const FLAGS_WITH_VALUE: &[&str] = &[
+    "-D",
+    "-U",
+    "-I",
+    "-include",
+    "-imacros",
+    "-isystem",
+    "-iquote",
+    "-idirafter",
+    "-x",
+    "-MF",
+    "-MQ",
+    "-MT",
+    "-o",
+];
+
+/// Find the flags all compiler invocations have in common
+///
+/// Each command consists of the compiler and its arguments; anything after `-c` is not CFLAGS but
+/// concrete input/output stuff, and thus not considered. Flags that take their value as a separate
+/// argument (like `-include file.h`) are compared together with that value. The common flags are
+/// returned in the order of the first command, along with the (deduplicated) flags that only some
+/// commands use.
+///
+/// There needs to be at least one command.
+pub fn consensus_arguments<'a>(commands: &[&'a [String]]) -> (Vec<&'a str>, Vec<&'a str>) {
+    let flags: Vec<Vec<&[String]>> = commands
+        .iter()
+        .map(|command| {
+            let command = &command[1..];
+            let end = command
+                .iter()
+                .position(|s| s == "-c")
+                .unwrap_or(command.len());
+            let mut units = vec![];
+            let mut i = 0;
+            while i < end {
+                let len = if FLAGS_WITH_VALUE.contains(&command[i].as_str()) && i + 1 < end {
+                    2
+                } else {
+                    1
+                };
+                units.push(&command[i..i + len]);
+                i += len;
+            }
+            units
+        })
+        .collect();
+
+    let consensus: Vec<&[String]> = flags[0]
+        .iter()
+        .copied()
+        .filter(|unit| flags[1..].iter().all(|f| f.contains(unit)))
+        .collect();
+    let mut dropped = vec![];
+    for &unit in flags.iter().flatten() {
+        if !consensus.contains(&unit) && !dropped.contains(&unit) {
+            dropped.push(unit);
+        }
+    }
+    let flatten = |units: Vec<&'a [String]>| {
+        units
+            .into_iter()
+            .flatten()
+            .map(|s| s.as_str())
+            .collect::<Vec<_>>()
+    };
+    (flatten(consensus), flatten(dropped))
+}
+
+/// Name of the macro RIOT defines for a used module
+///
+/// This follows what RIOT's makefiles/modules.inc.mk does (`tr 'a-z-' 'A-Z_'`): Only ASCII lower
+/// case letters are changed to upper case, and dashes become underscores (thus producing
+/// `MODULE_BOARDS_COMMON_SAMDX1_ARDUINO_BOOTLOADER`); anything else is left as it is.
+pub fn module_macro(module: &str) -> String {
+    let name: String = module
+        .chars()
+        .map(|c| match c {
+            'a'..='z' => c.to_ascii_uppercase(),
+            '-' => '_',
+            c => c,
+        })
+        .collect();
+    format!("MODULE_{}", name)
+}
+
+/// Check whether the value of a switch environment variable turns it on
+///
+/// Any value is taken as on, except for an empty one and `0`, so that `RIOT_SYS_STRICT=0` does
+/// not have the opposite of the intended effect.
+pub fn flag_enabled(value: &str) -> bool {
+    !value.is_empty() && value != "0"
+}
+
+/// Check whether a name can be used as an identifier in both C and Rust
+pub fn is_identifier(name: &str) -> bool {
+    !name.is_empty()
+        && !name.starts_with(|c: char| c.is_ascii_digit())
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// Find the value of a `-DNAME="value"` define in the CFLAGS, with its quotes removed
+pub fn find_string_define<'a>(cflags: &'a [String], name: &str) -> Option<&'a str> {
+    cflags
+        .iter()
+        .filter_map(|flag| {
+            flag.strip_prefix("-D")?
+                .strip_prefix(name)?
+                .strip_prefix('=')
+        })
+        .map(|value| {
+            value
+                .strip_prefix('"')
+                .and_then(|v| v.strip_suffix('"'))
+                .unwrap_or(value)
+        })
+        .next_back()
+}
+
+/// Release a RIOT version string belongs to (eg. `2022.01`)
+///
+/// Releases look like 2022.01 (or 2022.01-devel-123-gabcdef for builds in between); only the year
+/// and month are taken. Anything else (eg. versions of builds outside a git checkout) produces
+/// None.
+pub fn riot_release(version: &str) -> Option<&str> {
+    version.get(..7).filter(|r| {
+        r.bytes().enumerate().all(|(i, c)| {
+            if i == 4 {
+                c == b'.'
+            } else {
+                c.is_ascii_digit()
+            }
+        })
+    })
+}
+
+/// Find the modules RIOT announces through `-DMODULE_NAME` defines in the CFLAGS
+///
+/// The names are returned as they are in the define (eg. `GNRC_UDP`). Any value given to the
+/// define is ignored.
+pub fn find_modules(cflags: &[String]) -> Vec<&str> {
+    cflags
+        .iter()
+        .filter_map(|f| f.strip_prefix("-DMODULE_"))
+        .map(|m| m.split_once('=').map(|(name, _)| name).unwrap_or(m))
+        .collect()
+}
+
+/// Names of the constants and statics declared in C2Rust's output
+pub fn c2rust_constants(c2rust: &str) -> std::collections::BTreeSet<&str> {
+    c2rust
+        .lines()
+        .filter_map(|line| {
+            let line = line.trim_start();
+            let declaration = line
+                .strip_prefix("pub const ")
+                .or_else(|| line.strip_prefix("pub static mut "))
+                .or_else(|| line.strip_prefix("pub static "))?;
+            Some(declaration[..declaration.find(':')?].trim())
+        })
+        // `pub const unsafe fn macro_...(mut port: ...)` lines would be taken in otherwise
+        .filter(|name| is_identifier(name))
+        .collect()
+}
+
+/// Find constants of the C2Rust output whose names are also used for fields or arguments in the
+/// bindings
+///
+/// Where both are in scope, the field or argument name would be taken as a pattern matching the
+/// constant, which fails to build in confusing ways; this is what the GCLK and SERCOM renames work
+/// around.
+pub fn find_name_collisions(c2rust: &str, bindings: &str) -> Vec<String> {
+    let constants = c2rust_constants(c2rust);
+
+    // Fields and arguments look like `name: type`, as opposed to paths (`name::item`). Constants
+    // and statics are declared the same way, but are not patterns, and bindgen declares most of
+    // the same names C2Rust does, so they are skipped by looking at the words before.
+    let is_word_byte = |b: u8| b.is_ascii_alphanumeric() || b == b'_';
+    let bytes = bindings.as_bytes();
+    let mut collisions = std::collections::BTreeSet::new();
+    let mut word_start = None;
+    let mut previous_words = ["", ""];
+    for (i, &b) in bytes.iter().enumerate() {
+        if is_word_byte(b) {
+            word_start.get_or_insert(i);
+        } else if let Some(start) = word_start.take() {
+            let name = &bindings[start..i];
+            let is_declaration = matches!(
+                previous_words,
+                [_, "const"] | [_, "static"] | ["static", "mut"]
+            );
+            if b == b':'
+                && bytes.get(i + 1) != Some(&b':')
+                && !is_declaration
+                && constants.contains(name)
+            {
+                collisions.insert(name.to_string());
+            }
+            previous_words = [previous_words[1], name];
+        }
+    }
+    collisions.into_iter().collect()
+}
+
+/// Name of the file C2Rust (with `--emit-modules`) writes the translation of a given input file to
+///
+/// C2Rust uses the input file's stem as a module name, and thus replaces any characters that
+/// can't be in a Rust identifier with underscores.
+pub fn c2rust_output_name(infile: &str) -> String {
+    let stem = std::path::Path::new(infile)
+        .file_stem()
+        .and_then(|s| s.to_str())
+        .expect("Input file name has no stem");
+    let module: String = stem
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect();
+    format!("{}.rs", module)
+}
+
+/// Check whether a name matches a pattern in which `*` stands for any (possibly empty) sequence of
+/// characters, and `#` for a single ASCII digit
+pub fn glob_matches(pattern: &str, name: &str) -> bool {
+    match pattern.find(['*', '#']) {
+        None => pattern == name,
+        Some(i) => match name.strip_prefix(&pattern[..i]) {
+            None => false,
+            Some(name) if &pattern[i..=i] == "#" => {
+                name.starts_with(|c: char| c.is_ascii_digit())
+                    && glob_matches(&pattern[i + 1..], &name[1..])
+            }
+            Some(name) => (0..=name.len())
+                .filter(|j| name.is_char_boundary(*j))
+                .any(|j| glob_matches(&pattern[i + 1..], &name[j..])),
+        },
+    }
+}
+
+/// Replace whole words (identifiers, keywords or numbers) in Rust code
+///
+/// Words are maximal runs of ASCII alphanumerics and underscores, which is what a `\bWORD\b`
+/// regular expression would match on C2Rust's output. Each word for which `replacement` returns
+/// something is replaced with that; everything else is passed through unmodified.
+pub fn replace_words(code: &str, replacement: impl Fn(&str) -> Option<String>) -> String {
+    let is_word_char = |c: char| c.is_ascii_alphanumeric() || c == '_';
+
+    let mut result = String::with_capacity(code.len());
+    let mut rest = code;
+    while !rest.is_empty() {
+        let word_end = rest.find(|c| !is_word_char(c)).unwrap_or(rest.len());
+        if word_end > 0 {
+            let word = &rest[..word_end];
+            match replacement(word) {
+                Some(replaced) => result.push_str(&replaced),
+                None => result.push_str(word),
+            }
+            rest = &rest[word_end..];
+        } else {
+            let gap_end = rest.find(is_word_char).unwrap_or(rest.len());
+            result.push_str(&rest[..gap_end]);
+            rest = &rest[gap_end..];
+        }
+    }
+    result
+}
+
+/// Replace the function declarations in C2Rust's output with ... usually something pub, but
+/// special considerations may apply
+///
+/// Functions generated for `macro_functions` entries become (const) unsafe functions of their
+/// own, and functions whose names match any of the `private_patterns` are not made pub.
+pub fn rewrite_function_prefixes(
+    rustcode: &str,
+    macro_functions: &[(String, &str, &str, bool)],
+    private_patterns: &[&str],
+) -> String {
+    let mut rustcode_functionsreplaced = String::new();
+    let function_original_prefix = r#"unsafe extern "C" fn "#;
+
+    let mut functionchunks = rustcode.split(function_original_prefix);
+    rustcode_functionsreplaced.push_str(
+        functionchunks
+            .next()
+            .expect("Split produces at least a hit"),
+    );
+
+    for chunk in functionchunks {
+        let funcname = &chunk[..chunk.find('(').expect("Function has parentheses somewhere")];
+        let macro_details = if funcname.len() > 5 && &funcname[..6] == "macro_" {
+            macro_functions
+                .iter()
+                .find(|(macro_name, _, _, _)| funcname[6..] == *macro_name)
+        } else {
+            None
+        };
+
+        // Only the function's own line is rewritten: Any attributes C2Rust put in front of it
+        // (`#[inline]`, `#[cfg(...)]` etc.) are on the preceding lines and stay where they are.
+        // A `pub` on the function's line is taken out and then set anew, so that the prefixes
+        // below decide on visibility without ever producing a `pub pub`.
+        let line_start = rustcode_functionsreplaced
+            .rfind('\n')
+            .map(|i| i + 1)
+            .unwrap_or(0);
+        let original_visibility = if rustcode_functionsreplaced[line_start..].ends_with("pub ") {
+            rustcode_functionsreplaced.truncate(rustcode_functionsreplaced.len() - "pub ".len());
+            "pub "
+        } else {
+            ""
+        };
+
+        let (visibility, new_prefix) = match (funcname, macro_details) {
+            // used as a callback, therefore does need the extern "C" -- FIXME probably worth a RIOT issue
+            ("_evtimer_msg_handler" | "_evtimer_mbox_handler", _) => {
+                (original_visibility, function_original_prefix)
+            }
+
+            // Assigned by CMSIS to some const; see also riot-c2rust.h
+            ("__masked_builtin_arm_get_fpscr" | "__masked_builtin_arm_set_fpscr", _) => {
+                (original_visibility, function_original_prefix)
+            }
+
+            // same problem but from C2Rust's --translate-const-macros
+            ("__NVIC_SetPriority", _) => (original_visibility, function_original_prefix),
+
+            // As below (no need for extern), and they are const as declared ni the macro_functions
+            // list.
+            (_, Some((_, _, _, is_const))) => {
+                // Visibility is kept because that's already a "pub" in front of it, they were
+                // never static
+                (
+                    original_visibility,
+                    match is_const {
+                        // FIXME: These should be unsafe -- just because most of them are const doesn't
+                        // necessrily mean they're safe (just the first few happened to be, but that's
+                        // not this crate's place to assert)
+                        true => "const unsafe fn ",
+                        false => "unsafe fn ",
+                    },
+                )
+            }
+
+            // As below, but they were explicitly asked to stay out of the public inline module
+            _ if private_patterns
+                .iter()
+                .any(|pattern| glob_matches(pattern, funcname)) =>
+            {
+                ("", "unsafe fn ")
+            }
+
+            // The rest we don't need to call through the extern convention, but let's please make
+            // them pub to be usable
+            _ => ("pub ", "unsafe fn "),
+        };
+        rustcode_functionsreplaced.push_str(visibility);
+        rustcode_functionsreplaced.push_str(new_prefix);
+        rustcode_functionsreplaced.push_str(chunk);
+    }
+
+    rustcode_functionsreplaced
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn module_macros() {
+        for (module, expected) in [
+            ("gnrc_udp", "MODULE_GNRC_UDP"),
+            ("periph_i2c", "MODULE_PERIPH_I2C"),
+            (
+                "boards_common_samdx1-arduino-bootloader",
+                "MODULE_BOARDS_COMMON_SAMDX1_ARDUINO_BOOTLOADER",
+            ),
+            ("ztimer64", "MODULE_ZTIMER64"),
+            ("atmega256rfr2", "MODULE_ATMEGA256RFR2"),
+            ("CPU_STM32F4", "MODULE_CPU_STM32F4"),
+            ("Mixed-Case", "MODULE_MIXED_CASE"),
+        ] {
+            assert_eq!(module_macro(module), expected);
+        }
+    }
+
+    #[test]
+    fn globs() {
+        assert!(glob_matches("SERCOM#", "SERCOM3"));
+        assert!(!glob_matches("SERCOM#", "SERCOM"));
+        assert!(!glob_matches("SERCOM#", "SERCOM12"));
+        assert!(glob_matches("rust_*", "rust_helper"));
+        assert!(glob_matches("a*b#c", "axxb5c"));
+        assert!(!glob_matches("a*b#c", "axxbc"));
+    }
+
+    #[test]
+    fn global_renames() {
+        let renames = [("GCLK", "GLOBAL_$0"), ("SERCOM#", "GLOBAL_$0")];
+        let code = "(*GCLK).x = SERCOM0; let xGCLK = GCLK_A + SERCOM10; GLOBAL_GCLK";
+        let renamed = replace_words(code, |word| {
+            renames
+                .iter()
+                .find(|(pattern, _)| glob_matches(pattern, word))
+                .map(|(_, replacement)| replacement.replace("$0", word))
+        });
+        assert_eq!(
+            renamed,
+            "(*GLOBAL_GCLK).x = GLOBAL_SERCOM0; let xGCLK = GCLK_A + SERCOM10; GLOBAL_GCLK"
+        );
+    }
+
+    #[test]
+    fn string_defines() {
+        let cflags: Vec<String> = [
+            "-DRIOT_BOARD=\"native\"",
+            "-DRIOT_VERSION=\"2022.01-devel\"",
+            "-DRIOT_BOARD=\"samr21-xpro\"",
+            "-DRIOT_CPU=samd21",
+        ]
+        .iter()
+        .map(|s| s.to_string())
+        .collect();
+        assert_eq!(
+            find_string_define(&cflags, "RIOT_BOARD"),
+            Some("samr21-xpro")
+        );
+        assert_eq!(find_string_define(&cflags, "RIOT_CPU"), Some("samd21"));
+        assert_eq!(find_string_define(&cflags, "RIOT"), None);
+    }
+
+    #[test]
+    fn flags() {
+        assert!(flag_enabled("1"));
+        assert!(flag_enabled("yes"));
+        assert!(!flag_enabled("0"));
+        assert!(!flag_enabled(""));
+    }
+
+    #[test]
+    fn releases() {
+        assert_eq!(riot_release("2022.01"), Some("2022.01"));
+        assert_eq!(riot_release("2022.01-devel-123-gabc"), Some("2022.01"));
+        assert_eq!(riot_release("2022.1"), None);
+        assert_eq!(riot_release("UNKNOWN (builds outside git)"), None);
+        assert_eq!(riot_release("2022-01"), None);
+    }
+
+    #[test]
+    fn consensus() {
+        let split =
+            |command: &str| -> Vec<String> { command.split(' ').map(|s| s.to_string()).collect() };
+        let c = split("gcc -DX -MQ a.o -include a.h -Wall -x c -DA -c a.c -o a.o");
+        let cxx = split("g++ -DX -MQ b.o -include b.h -Wall -x c++ -DA -fno-rtti -c b.cpp");
+        assert_eq!(
+            consensus_arguments(&[&c, &cxx]),
+            (
+                vec!["-DX", "-Wall", "-DA"],
+                vec![
+                    "-MQ",
+                    "a.o",
+                    "-include",
+                    "a.h",
+                    "-x",
+                    "c",
+                    "-MQ",
+                    "b.o",
+                    "-include",
+                    "b.h",
+                    "-x",
+                    "c++",
+                    "-fno-rtti"
+                ]
+            )
+        );
+
+        let single = split("clang -I /usr/include -D X=1 -c a.c");
+        assert_eq!(
+            consensus_arguments(&[&single]),
+            (vec!["-I", "/usr/include", "-D", "X=1"], vec![])
+        );
+    }
+
+    #[test]
+    fn name_collisions() {
+        let c2rust = "pub const GCLK: u32 = 1;
+pub const GNRC_NETIF_NUMOF: u32 = 1;
+pub static mut sched_active_thread: *mut thread_t = 0 as *mut thread_t;
+pub static mut SERCOM0: u32 = 0;
+pub const unsafe fn macro_GPIO_PIN(mut port: libc::c_uint, mut pin: libc::c_uint) -> gpio_t {
+";
+        let bindings = "pub const GNRC_NETIF_NUMOF: u32 = 1;
+extern \"C\" {
+    pub static mut sched_active_thread: *mut thread_t;
+}
+pub struct clock_config_t {
+    pub GCLK: u8,
+    pub other: core::GNRC_NETIF_NUMOF::x,
+}
+extern \"C\" {
+    pub fn sercom_init(SERCOM0: u32);
+}
+";
+        assert_eq!(
+            c2rust_constants(c2rust).into_iter().collect::<Vec<_>>(),
+            vec!["GCLK", "GNRC_NETIF_NUMOF", "SERCOM0", "sched_active_thread"]
+        );
+        assert_eq!(
+            find_name_collisions(c2rust, bindings),
+            vec!["GCLK".to_string(), "SERCOM0".to_string()]
+        );
+    }
+
+    #[test]
+    fn function_prefixes() {
+        let c2rust = r#"#[inline]
+unsafe extern "C" fn irq_is_in() -> bool {
+    return false;
+}
+#[no_mangle]
+#[inline]
+pub unsafe extern "C" fn macro_MUTEX_INIT() -> mutex_t {
+    return mutex_t { queue: list_node_t { next: 0 as *mut list_node } };
+}
+#[no_mangle]
+pub unsafe extern "C" fn macro_LED0_ON() {}
+#[inline]
+unsafe extern "C" fn rust_helper_1() -> i32 {
+    return 0;
+}
+#[no_mangle]
+pub unsafe extern "C" fn _evtimer_msg_handler(mut arg: *mut libc::c_void) {}
+"#;
+        let macro_functions = [
+            ("MUTEX_INIT".to_string(), "mutex_t", "void", true),
+            ("LED0_ON".to_string(), "void", "void", false),
+        ];
+        let expected = r#"#[inline]
+pub unsafe fn irq_is_in() -> bool {
+    return false;
+}
+#[no_mangle]
+#[inline]
+pub const unsafe fn macro_MUTEX_INIT() -> mutex_t {
+    return mutex_t { queue: list_node_t { next: 0 as *mut list_node } };
+}
+#[no_mangle]
+pub unsafe fn macro_LED0_ON() {}
+#[inline]
+unsafe fn rust_helper_1() -> i32 {
+    return 0;
+}
+#[no_mangle]
+pub unsafe extern "C" fn _evtimer_msg_handler(mut arg: *mut libc::c_void) {}
+"#;
+        assert_eq!(
+            rewrite_function_prefixes(c2rust, &macro_functions, &["rust_*"]),
+            expected
+        );
+    }
+}
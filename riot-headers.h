@@ -80,10 +80,21 @@
 #endif
 #include <net/gnrc.h>
 #include <net/gnrc/udp.h>
+#include <net/inet_csum.h>
 #include <net/gnrc/pktbuf.h>
 #include <net/gnrc/ipv6.h>
 #include <net/gnrc/nettype.h>
 #include <net/gnrc/netapi.h>
+#include <net/gnrc/netif/hdr.h>
+#ifdef MODULE_L2UTIL
+#include <net/l2util.h>
+#endif
+#ifdef MODULE_GNRC_IPV6_NIB
+#include <net/gnrc/ipv6/nib.h>
+#endif
+#ifdef MODULE_GNRC_SIXLOWPAN_CTX
+#include <net/gnrc/sixlowpan/ctx.h>
+#endif
 #ifdef MODULE_GNRC_ICMPV6
 #include "net/gnrc/icmpv6.h"
 #endif
@@ -115,7 +126,19 @@
 #ifdef MODULE_ZTIMER_PERIODIC
 #include <ztimer/periodic.h>
 #endif
+#ifdef MODULE_ZTIMER64
+#include <ztimer64.h>
+#endif
 #include <mutex.h>
+#ifdef MODULE_LUID
+#include <luid.h>
+#endif
+#ifdef MODULE_ISRPIPE
+#include <isrpipe.h>
+#endif
+#ifdef MODULE_CRYPTO
+#include <crypto/ciphers.h>
+#endif
 #ifdef MODULE_CORD_COMMON
 #include <net/cord/common.h>
 #endif
@@ -50,6 +50,118 @@
 //! passed down to dependent crates as they were before. (The passed down CC will just always be
 //! clang).
 //!
+//! If the compile commands were produced with a compiler cache in front of the compiler (`ccache`,
+//! `sccache` or `distcc`), that wrapper is skipped and the compiler after it is used. Names of
+//! further wrappers can be given in `RIOT_CC_WRAPPERS`, separated by spaces.
+//!
+//! When the compile commands contain several entries (eg. in projects mixing C and C++), only the
+//! flags that all entries have in common are used; the others are listed in a warning. The compiler
+//! is taken from the first entry.
+//!
+//! Some flags GCC understands are not understood by clang (which bindgen and C2Rust use), and are
+//! removed from the CFLAGS. Further flags that need to be removed (eg. for toolchains this crate does
+//! not know yet) can be given in `RIOT_SYS_DROP_CFLAGS`, separated by spaces; they are matched
+//! exactly.
+//!
+//! ## Build information
+//!
+//! The RIOT version the crate is built against is available as [`RIOT_VERSION`] (taken from the
+//! `RIOT_VERSION` define RIOT passes in its CFLAGS). For release versions, a `riot_version` cfg is
+//! set for this crate as well (eg. `riot_version="2022.01"`).
+//!
+//! Likewise, the board and CPU names are available as [`RIOT_BOARD`] and [`RIOT_CPU`]. A unique
+//! device ID can be read using `cpuid_get` on boards with the `periph_cpuid` module.
+//!
+//! For every module RIOT is built with, a `riot_module="NAME"` cfg is set for this crate (eg.
+//! `riot_module="gnrc_udp"`). As Cargo does not pass cfgs on to other crates, the list of modules
+//! (as in their `MODULE_` defines, separated by spaces) is also available to build scripts of
+//! crates depending on riot-sys as `DEP_RIOT_SYS_MODULES`.
+//!
+//! ## Tuning the bindings
+//!
+//! By default, bindgen translates C enums into plain constants. `RIOT_BINDGEN_ENUM_STYLE` can
+//! change that: It takes a space separated list of either a bare style (`rust`,
+//! `rust_non_exhaustive`, `newtype`, `bitfield`, `consts` or `moduleconsts`, which then applies to
+//! all enums), or a `style=pattern` pair (which applies the style only to enums whose name matches
+//! the pattern). For example, `RIOT_BINDGEN_ENUM_STYLE="newtype=netopt_t"` makes `netopt_t` a
+//! newtype and leaves all other enums as they are. Note that riot-wrappers expects the default
+//! style; changing it globally is only useful when riot-sys is used directly.
+//!
+//! Functions C2Rust produces are all made public in the inline module. Helpers that are only an
+//! artifact of the transpilation and collide with other names can be kept private by listing their
+//! names in `RIOT_C2RUST_PRIVATE_PATTERNS`, separated by spaces, where `*` matches any sequence of
+//! characters (eg. `RIOT_C2RUST_PRIVATE_PATTERNS="rust_*"`).
+//!
+//! All structs get a `Default` implementation, which zero-initializes them. Where a zeroed value is
+//! not a meaningful default, that implementation can be suppressed by listing the type names (or
+//! patterns) in `RIOT_BINDGEN_NO_DEFAULT`, separated by spaces. An example is `sock_udp_ep_t`, whose
+//! zeroed form has the unspecified address family; `macro_SOCK_IPV6_EP_ANY()` is what is usually
+//! wanted instead.
+//!
+//! Tools that need to look at the bindings of several RIOT configurations at once can set
+//! `RIOT_SYS_BINDINGS_MOD` to a module name; the generated `bindings.rs` then wraps everything in a
+//! `pub mod` of that name (which expects a `libc` module to be in scope where it is included). As
+//! this moves all the bindgen generated items out of the crate's top level, it is not suitable for
+//! regular builds, and riot-wrappers will not build against it.
+//!
+//! Initializer macros (like `MUTEX_INIT`) are made available as `const unsafe fn macro_NAME()` for a
+//! fixed set of types. Applications can add more by pointing `RIOT_SYS_EXTRA_INITIALIZERS` to a JSON
+//! file that lists them along with the type they produce, as in `[{"macro": "MY_MODULE_INIT", "type":
+//! "my_module_t"}]`. Like the built-in ones, each is only available if the macro is defined.
+//!
+//! Some boards define global constants that clash with field or argument names in the bindings (the
+//! build script warns about those). A few known ones (like `GCLK` and `SERCOM0` to `SERCOM9`) are
+//! renamed to `GLOBAL_GCLK` etc.; more can be added in `RIOT_SYS_RENAME_GLOBALS` as space
+//! separated `pattern=replacement` items, where `*` in the pattern matches any characters, `#`
+//! matches a single digit, and `$0` in the replacement is the original name (eg.
+//! `RIOT_SYS_RENAME_GLOBALS="TCC#=GLOBAL_$0"`).
+//!
+//! ## Stub builds
+//!
+//! For type-checking code that depends on riot-sys on a host without any RIOT build environment,
+//! `RIOT_SYS_STUB=1` can be set instead of the above variables. The build script then does not
+//! run bindgen or C2Rust at all, and produces stub bindings that only declare a few of the most
+//! commonly used types (mostly as opaque structs; `ipv6_addr_t` has its `u8` view) and functions.
+//! The CC, CFLAGS and module list passed on to dependent crates are empty. Such a build is not
+//! functional; it can not be linked against RIOT, and most items are absent from it, so code using
+//! anything beyond those items still fails to type-check.
+//!
+//! Where C2Rust can not be installed or fails on some board's headers, `RIOT_SYS_SKIP_C2RUST=1`
+//! produces a build that is functional, but only contains what bindgen produces: The `inline`
+//! module is empty, so static inline functions (including those otherwise re-exported at the top
+//! level, like `irq_disable` on most platforms) and the `macro_` functions are unavailable.
+//!
+//! ## Diagnostics
+//!
+//! When the generated bindings do not come out as expected, setting `RIOT_SYS_DUMP_CONFIG` to a
+//! file name makes the build script write the configuration it actually used into that file as
+//! JSON: the C compiler, the CFLAGS after filtering (and the blocklist that was applied to them),
+//! the RIOT modules found in the CFLAGS, and the arguments C2Rust was run with. The file is written
+//! before bindgen runs (and updated before C2Rust runs), so it is also there when bindgen fails.
+//! That file is useful to attach to bug reports, or to compare between a working and a broken
+//! build.
+//!
+//! With `RIOT_SYS_VERBOSE` set, the build script reports (as Cargo warnings) additional details on
+//! what the bindings are generated from: the include directories and defines found in the CFLAGS,
+//! and the target bindgen produces bindings for (along with Rust's pointer width, which it needs to
+//! match).
+//!
+//! For quickly checking whether a set of CFLAGS works at all, `RIOT_SYS_BINDGEN_ONLY=1` stops the
+//! build script after bindgen has run. C2Rust is not run, so none of the static inline functions or
+//! `macro_` functions are available in such a build.
+//!
+//! When the preprocessor or C2Rust fails, the build script reports that as a warning and exits with
+//! the tool's exit code. In automated builds where that is easily missed, setting `RIOT_SYS_STRICT=1`
+//! makes it panic instead, with the tool's complete error output in the panic message.
+//!
+//! C2Rust's output is kept between builds, and reused as long as the compiler, the CFLAGS and this
+//! crate's header files are unchanged, and neither the C2Rust binary nor any of the RIOT headers
+//! (as seen by bindgen) have a different modification time. Where that is not enough,
+//! `cargo clean -p riot-sys` forces C2Rust to be run again.
+//!
+//! The on/off switches (`RIOT_SYS_STUB`, `RIOT_SYS_SKIP_C2RUST`, `RIOT_SYS_VERBOSE`,
+//! `RIOT_SYS_BINDGEN_ONLY` and `RIOT_SYS_STRICT`) are on for any value except an empty one and `0`.
+//!
 //! ## Extension
 //!
 //! Currently, only a subset of all the RIOT headers is processed; all the relevant
@@ -69,6 +181,9 @@
 //!     $ rustup component add --toolchain nightly-2019-12-05 rustfmt rustc-dev
 //!     $ cargo +nightly-2019-12-05 install --locked --debug --path c2rust
 //!
+//! To use a C2Rust binary that is not on the PATH (eg. a pinned or patched build), set
+//! `RIOT_C2RUST_BIN` (or `C2RUST`) to its path.
+//!
 //! [C2Rust]: https://c2rust.com/
 //!
 //! ---
@@ -101,4 +216,5 @@ mod bindgen;
 pub mod inline;
 
 include!(concat!(env!("OUT_DIR"), "/toplevel_from_inline.rs"));
+include!(concat!(env!("OUT_DIR"), "/riot_defines.rs"));
 pub use bindgen::*;
@@ -0,0 +1,8 @@
+//! Tests for the helper functions of the build script
+//!
+//! Cargo does not run tests in build scripts, so the helpers are built into this test a second
+//! time. Like any test of this crate, it needs the RIOT build environment, or `RIOT_SYS_STUB=1`.
+
+#[allow(dead_code)]
+#[path = "../build/helpers.rs"]
+mod helpers;